@@ -0,0 +1,57 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
+
+use crate::storage::{Storage, StorageError};
+
+/// In-memory `Storage` backend for tests. Records every `put` in a `HashMap` and serves it back
+/// on `get`, so upload tests can assert key naming, returned URLs, and stored bytes
+/// deterministically and offline, without live AWS credentials.
+pub struct MockBackend {
+    objects: Mutex<HashMap<String, Vec<u8>>>,
+    base_url: String,
+}
+
+impl MockBackend {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            objects: Mutex::new(HashMap::new()),
+            base_url: base_url.into(),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new("https://mock-bucket.test/")
+    }
+}
+
+#[async_trait]
+impl Storage for MockBackend {
+    async fn put(
+        &self,
+        key: &str,
+        bytes: Vec<u8>,
+        _content_type: &str,
+    ) -> Result<String, StorageError> {
+        self.objects
+            .lock()
+            .map_err(|_| StorageError::PutFailed)?
+            .insert(key.to_string(), bytes);
+        Ok(format!("{}{key}", self.base_url))
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        self.objects
+            .lock()
+            .map_err(|_| StorageError::GetFailed)?
+            .get(key)
+            .cloned()
+            .ok_or(StorageError::NotFound)
+    }
+}
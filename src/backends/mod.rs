@@ -0,0 +1,5 @@
+pub mod mock;
+pub mod s3;
+
+pub use mock::MockBackend;
+pub use s3::S3Backend;
@@ -0,0 +1,159 @@
+use async_trait::async_trait;
+use aws_sdk_s3::{
+    model::{CompletedMultipartUpload, CompletedPart},
+    types::ByteStream,
+    Client,
+};
+
+use crate::{
+    get_image_url_from_image_name,
+    storage::{Storage, StorageError},
+    S3Config, MULTIPART_PART_SIZE_BYTES, MULTIPART_THRESHOLD_BYTES,
+};
+
+/// The original S3 storage logic, now behind the `Storage` trait so the image operations in
+/// this crate can be pointed at any backend. Payloads over `MULTIPART_THRESHOLD_BYTES` are
+/// uploaded as a multipart upload transparently.
+pub struct S3Backend {
+    client: Client,
+    config: S3Config,
+}
+
+impl S3Backend {
+    pub fn new(client: Client, config: S3Config) -> Self {
+        Self { client, config }
+    }
+}
+
+#[async_trait]
+impl Storage for S3Backend {
+    async fn put(
+        &self,
+        key: &str,
+        bytes: Vec<u8>,
+        content_type: &str,
+    ) -> Result<String, StorageError> {
+        if bytes.len() > MULTIPART_THRESHOLD_BYTES {
+            self.upload_multipart(key, bytes, content_type).await?;
+        } else {
+            self.client
+                .put_object()
+                .bucket(&self.config.bucket_name)
+                .key(key)
+                .body(ByteStream::from(bytes))
+                .content_type(content_type)
+                .set_grant_read(
+                    Some("uri=http://acs.amazonaws.com/groups/global/AllUsers".to_string()), // grant read access to everyone
+                )
+                .send()
+                .await
+                .map_err(|_| StorageError::PutFailed)?;
+        }
+
+        Ok(get_image_url_from_image_name(key, &self.config))
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.config.bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|_| StorageError::NotFound)?;
+        let bytes = output
+            .body
+            .collect()
+            .await
+            .map_err(|_| StorageError::GetFailed)?;
+        Ok(bytes.into_bytes().to_vec())
+    }
+}
+
+impl S3Backend {
+    /// Uploads `bytes` to `key` as a multipart upload, splitting it into
+    /// `MULTIPART_PART_SIZE_BYTES` chunks. Aborts the upload on any part or completion failure
+    /// so no incomplete upload is left dangling in the bucket.
+    async fn upload_multipart(
+        &self,
+        key: &str,
+        bytes: Vec<u8>,
+        content_type: &str,
+    ) -> Result<(), StorageError> {
+        let create_output = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.config.bucket_name)
+            .key(key)
+            .content_type(content_type)
+            .set_grant_read(
+                Some("uri=http://acs.amazonaws.com/groups/global/AllUsers".to_string()), // grant read access to everyone
+            )
+            .send()
+            .await
+            .map_err(|_| StorageError::PutFailed)?;
+        let upload_id = create_output.upload_id().ok_or(StorageError::PutFailed)?;
+
+        let mut completed_parts = Vec::new();
+        for (index, chunk) in bytes.chunks(MULTIPART_PART_SIZE_BYTES).enumerate() {
+            let part_number = index as i32 + 1;
+            match self
+                .client
+                .upload_part()
+                .bucket(&self.config.bucket_name)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .body(ByteStream::from(chunk.to_vec()))
+                .send()
+                .await
+            {
+                Ok(output) => completed_parts.push(
+                    CompletedPart::builder()
+                        .set_e_tag(output.e_tag().map(str::to_string))
+                        .part_number(part_number)
+                        .build(),
+                ),
+                Err(_) => {
+                    let _ = self
+                        .client
+                        .abort_multipart_upload()
+                        .bucket(&self.config.bucket_name)
+                        .key(key)
+                        .upload_id(upload_id)
+                        .send()
+                        .await;
+                    return Err(StorageError::PutFailed);
+                }
+            }
+        }
+
+        let complete_result = self
+            .client
+            .complete_multipart_upload()
+            .bucket(&self.config.bucket_name)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(completed_parts))
+                    .build(),
+            )
+            .send()
+            .await;
+        if complete_result.is_err() {
+            let _ = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.config.bucket_name)
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+            return Err(StorageError::PutFailed);
+        }
+
+        Ok(())
+    }
+}
@@ -1,16 +1,20 @@
-use std::{fs, str::FromStr};
+use std::{io::Cursor, str::FromStr, time::Duration};
 
 #[allow(unused)]
-use aws_sdk_s3::{
-    error::PutObjectError,
-    types::{ByteStream, SdkError},
-    Client,
-};
+use aws_credential_types::provider::ProvideCredentials;
+#[allow(unused)]
+use aws_sdk_s3::{presigning::config::PresigningConfig, Client, Region};
 
 use css_color_parser::Color;
 use dotenv::dotenv;
-use image::GenericImageView;
-use image_base64::to_base64;
+use image::{DynamicImage, GenericImageView};
+use webp::Encoder;
+
+mod backends;
+mod storage;
+
+pub use backends::{MockBackend, S3Backend};
+pub use storage::{Storage, StorageError};
 
 pub const DEFAULT_BUCKET_NAME: &str = "images-robinbrick";
 pub const BASE_BUCKET_URL: &str = "https://images-robinbrick.s3.eu-west-1.amazonaws.com/";
@@ -19,8 +23,12 @@ pub const BACKGROUND_IMAGE_WIDTH: u32 = 1400;
 pub const BACKGROUND_IMAGE_HEIGHT: u32 = 400;
 pub const MAX_LOGO_WIDTH: u32 = 1000;
 pub const MAX_LOGO_HEIGHT: u32 = 300;
-/// Env variable names that must be in here for this library to work
-const ALL_VARS: [&str; 3] = ["AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY", "AWS_REGION"];
+/// Quality passed to the WebP encoder for responsive renditions (0-100)
+pub const RESPONSIVE_WEBP_QUALITY: f32 = 80.0;
+/// Payload size above which `S3Backend::put` switches to a multipart upload
+pub const MULTIPART_THRESHOLD_BYTES: usize = 8 * 1024 * 1024;
+/// Size of each part in a multipart upload (S3 requires at least 5 MiB, except the last part)
+pub const MULTIPART_PART_SIZE_BYTES: usize = 5 * 1024 * 1024;
 
 #[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct EncodedImage<'a> {
@@ -35,60 +43,215 @@ pub enum S3Error {
     ImageDecodeError,
     ImageEncodeError,
     NotFoundError,
+    UploadError,
+    PresignError,
+    CredentialsError,
+}
+
+/// Describes which bucket, region and (optionally) S3-compatible endpoint this library talks to.
+/// Lets a single process target MinIO, Wasabi, a second bucket, etc, instead of the
+/// hardcoded `DEFAULT_BUCKET_NAME` / `BASE_BUCKET_URL`.
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    pub bucket_name: String,
+    pub region: String,
+    /// Set this to target an S3-compatible store (MinIO, Wasabi, ...) instead of AWS.
+    pub endpoint_url: Option<String>,
+    /// Overrides the URL prefix returned by `get_image_url_from_image_name`.
+    /// Defaults to a path-style URL under `endpoint_url` if that's set, otherwise to the standard
+    /// AWS virtual-hosted-style URL for `bucket_name` / `region` (see `resolved_base_url`).
+    pub base_url: Option<String>,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            bucket_name: DEFAULT_BUCKET_NAME.to_string(),
+            region: "eu-west-1".to_string(),
+            endpoint_url: None,
+            base_url: None,
+        }
+    }
+}
+
+impl S3Config {
+    /// The URL prefix to join an image name onto: `base_url` if set; otherwise a path-style URL
+    /// under `endpoint_url` if that's set (we address S3-compatible endpoints path-style, see
+    /// `start_s3_aws_connection`); otherwise the standard AWS virtual-hosted-style URL for
+    /// `bucket_name` / `region`.
+    pub fn resolved_base_url(&self) -> String {
+        match (&self.base_url, &self.endpoint_url) {
+            (Some(base_url), _) => base_url.clone(),
+            (None, Some(endpoint_url)) => {
+                format!("{}/{}/", endpoint_url.trim_end_matches('/'), self.bucket_name)
+            }
+            (None, None) => format!(
+                "https://{}.s3.{}.amazonaws.com/",
+                self.bucket_name, self.region
+            ),
+        }
+    }
 }
 
 /// Entry point of this library.
-/// Make sure to have all the credentials defined in the .env file or environment variables before calling this method.
-/// Otherwise, it Will panic.
+/// Resolves credentials from the standard provider chain — environment variables, a named
+/// profile in `~/.aws/credentials`, then the IMDS/instance-metadata endpoint — in that order.
+/// Returns `S3Error::CredentialsError` instead of panicking when none of them resolve, so this
+/// runs unchanged on EC2/ECS and in local dev with a profile, not just with env vars set.
 #[allow(unused)]
-pub async fn start_s3_aws_connection() -> Client {
+pub async fn start_s3_aws_connection(config: &S3Config) -> Result<Client, S3Error> {
     dotenv().ok();
-    for var in ALL_VARS {
-        match dotenv::var(var) {
-            Ok(_) => {},
-            Err(_) => panic!("Env variable: {var} not found in your environment. You must have these variables: {:?}. in order to use this library", ALL_VARS),
-        }
+
+    let mut config_loader = aws_config::from_env().region(Region::new(config.region.clone()));
+    if let Some(endpoint_url) = &config.endpoint_url {
+        config_loader = config_loader.endpoint_url(endpoint_url.clone());
     }
-    let config = aws_config::load_from_env().await;
-    aws_sdk_s3::Client::new(&config)
+    let aws_config = config_loader.load().await;
+
+    let credentials_provider = aws_config
+        .credentials_provider()
+        .ok_or(S3Error::CredentialsError)?;
+    credentials_provider
+        .provide_credentials()
+        .await
+        .map_err(|_| S3Error::CredentialsError)?;
+
+    let mut s3_config_builder = aws_sdk_s3::config::Builder::from(&aws_config);
+    if config.endpoint_url.is_some() {
+        // S3-compatible stores generally don't support virtual-hosted-style addressing
+        s3_config_builder = s3_config_builder.force_path_style(true);
+    }
+    Ok(Client::from_conf(s3_config_builder.build()))
 }
 
-/// Call service::start_s3_aws_connection().await first to get the client
-/// Uploads an image to AWS s3 bucket and returns the URL to the publicly accessible image
+/// Call service::start_s3_aws_connection().await first to get a client to build a `storage`
+/// backend with (e.g. `S3Backend::new`).
+/// Uploads an image through `storage` and returns the URL to the stored image.
 /// Pass a None value to the file_name_opt to let it get assigned a random number as the name,
 /// Or pass a name and an extension to make it use that.
 pub async fn upload_image_in_base64<'a>(
-    client: &Client,
+    storage: &dyn Storage,
     image: EncodedImage<'a>,
     file_name_opt: Option<&str>,
-) -> Result<String, SdkError<PutObjectError>> {
+) -> Result<String, S3Error> {
     let conversion_tuple = decode_base64_to_image(image);
-    let body = ByteStream::from(conversion_tuple.0);
+    let bytes = conversion_tuple.0;
 
     let file_name = match file_name_opt {
         Some(file_name) => file_name,
         None => conversion_tuple.1.as_str(),
     };
+    let content_type = format!(
+        "image/{}",
+        extract_extension_from_file_name(file_name).unwrap_or("octet-stream")
+    );
+
+    storage
+        .put(file_name, bytes, &content_type)
+        .await
+        .map_err(|_| S3Error::UploadError)
+}
+
+/// Pulls the part after the last `.` in a file name, e.g. `Some("png")` for `"photo.png"`,
+/// or `None` if the name has no `.`.
+fn extract_extension_from_file_name(file_name: &str) -> Option<&str> {
+    file_name.rsplit_once('.').map(|(_, extension)| extension)
+}
+
+/// Join the configured base bucket url and the image name
+pub fn get_image_url_from_image_name(image_name: &str, config: &S3Config) -> String {
+    format!("{}{image_name}", config.resolved_base_url())
+}
+
+/// Generates a time-limited URL that lets anyone download `key` without the bucket being public.
+pub async fn presign_get_url(
+    client: &Client,
+    key: &str,
+    expires_in: Duration,
+    config: &S3Config,
+) -> Result<String, S3Error> {
+    let presigning_config =
+        PresigningConfig::expires_in(expires_in).map_err(|_| S3Error::PresignError)?;
+    let presigned = client
+        .get_object()
+        .bucket(&config.bucket_name)
+        .key(key)
+        .presigned(presigning_config)
+        .await
+        .map_err(|_| S3Error::PresignError)?;
+    Ok(presigned.uri().to_string())
+}
 
-    match client
+/// Generates a time-limited URL that lets a client upload `key` directly to S3, bypassing this
+/// library entirely for the bytes themselves.
+pub async fn presign_put_url(
+    client: &Client,
+    key: &str,
+    expires_in: Duration,
+    config: &S3Config,
+) -> Result<String, S3Error> {
+    let presigning_config =
+        PresigningConfig::expires_in(expires_in).map_err(|_| S3Error::PresignError)?;
+    let presigned = client
         .put_object()
-        .bucket(DEFAULT_BUCKET_NAME)
-        .key(file_name)
-        .body(body)
-        .set_grant_read(
-            Some("uri=http://acs.amazonaws.com/groups/global/AllUsers".to_string()), // grant read access to everyone
-        )
-        .send()
+        .bucket(&config.bucket_name)
+        .key(key)
+        .presigned(presigning_config)
         .await
-    {
-        Ok(_) => Ok(get_image_url_from_image_name(file_name)),
-        Err(e) => Err(e),
+        .map_err(|_| S3Error::PresignError)?;
+    Ok(presigned.uri().to_string())
+}
+
+/// Call service::start_s3_aws_connection().await first to get a client to build a `storage`
+/// backend with (e.g. `S3Backend::new`).
+/// Produces a set of width-scaled WebP renditions of the source image and uploads each one
+/// through `storage`, rather than a single file. `widths` are target pixel widths
+/// (e.g. `[320, 640, 1080]`); a width larger than the source is left at the source's own width
+/// instead of upscaling.
+/// Pass a None value to file_name_opt to let the renditions share a random base name,
+/// or pass a base name (without extension) to make it use that, e.g. `name-640.webp`.
+/// Returns the URLs of the uploaded renditions, in the same order as `widths`.
+pub async fn upload_responsive_set<'a>(
+    storage: &dyn Storage,
+    image: EncodedImage<'a>,
+    file_name_opt: Option<&str>,
+    widths: &[u32],
+) -> Result<Vec<String>, S3Error> {
+    let decoded_image = decode_base64_to_image(image);
+    let source = match image::load_from_memory(&decoded_image.0) {
+        Ok(image) => image,
+        Err(_) => return Err(S3Error::ImageDecodeError),
+    };
+
+    let base_name = match file_name_opt {
+        Some(name) => name.to_string(),
+        None => rand::random::<u64>().to_string(),
+    };
+
+    let mut urls = Vec::with_capacity(widths.len());
+    for width in widths {
+        let rendition = if *width >= source.width() {
+            source.clone()
+        } else {
+            source.resize(*width, u32::MAX, image::imageops::FilterType::Lanczos3)
+        };
+        let webp_bytes = encode_to_webp(&rendition)?;
+        let key = format!("{base_name}-{width}.webp");
+
+        let url = storage
+            .put(&key, webp_bytes, "image/webp")
+            .await
+            .map_err(|_| S3Error::UploadError)?;
+        urls.push(url);
     }
+
+    Ok(urls)
 }
 
-/// Join the base bucket url and the image name
-pub fn get_image_url_from_image_name(image_name: &str) -> String {
-    format!("{BASE_BUCKET_URL}{image_name}")
+/// Encodes an image to WebP bytes at `RESPONSIVE_WEBP_QUALITY`
+fn encode_to_webp(image: &DynamicImage) -> Result<Vec<u8>, S3Error> {
+    let encoder = Encoder::from_image(image).map_err(|_| S3Error::ImageEncodeError)?;
+    Ok(encoder.encode(RESPONSIVE_WEBP_QUALITY).to_vec())
 }
 
 /// Converts base64str to byte vec and a filename
@@ -167,20 +330,18 @@ pub fn change_background<'a>(
             *pixel = image::Rgba([color.r, color.g, color.b as u8, 255]);
         }
     }
-    let file_name = format!("{}.jpeg", rand::random::<u64>());
-    match background.save_with_format(file_name.clone(), image::ImageFormat::Jpeg) {
+    let mut jpeg_bytes: Vec<u8> = Vec::new();
+    match DynamicImage::from(background).write_to(
+        &mut Cursor::new(&mut jpeg_bytes),
+        image::ImageFormat::Jpeg,
+    ) {
         Ok(_) => {}
         Err(_) => return Err(S3Error::ImageEncodeError),
     };
-    let image_buf = match image::open(file_name.as_str()) {
-        Ok(_) => to_base64(file_name.as_str()),
-        Err(_) => return Err(S3Error::NotFoundError),
-    };
-    match fs::remove_file(file_name) {
-        Ok(_) => {}
-        Err(_) => {}
-    };
-    Ok(image_buf)
+    Ok(format!(
+        "data:image/jpeg;base64,{}",
+        base64::encode(jpeg_bytes)
+    ))
 }
 
 #[cfg(test)]
@@ -191,7 +352,7 @@ mod tests {
 
     use crate::{
         change_background, start_s3_aws_connection, upload_image_in_base64, EncodedImage,
-        BASE_BUCKET_URL,
+        MockBackend, S3Config, Storage,
     };
 
     #[test]
@@ -211,9 +372,10 @@ mod tests {
         fs::write("path.png", image_bytes).unwrap();
     }
     /// Connects to aws and attempts to list buckets
+    #[ignore = "requires live AWS credentials"]
     #[tokio::test]
     async fn connect_to_aws() {
-        let client = start_s3_aws_connection().await;
+        let client = start_s3_aws_connection(&S3Config::default()).await.unwrap();
         let buckets = client.list_buckets().send().await;
         assert!(
             matches!(buckets, Ok(_)),
@@ -224,11 +386,12 @@ mod tests {
 
     /// Checks that the bucket named images-robinbrick exists and is available
     /// This is only for robinbrick internal use.
+    #[ignore = "requires live AWS credentials and the images-robinbrick bucket"]
     #[tokio::test]
     async fn test_that_images_bucket_exists() {
         let bucket_name = "images-robinbrick";
 
-        let client = start_s3_aws_connection().await;
+        let client = start_s3_aws_connection(&S3Config::default()).await.unwrap();
         let bucket_res = client.list_buckets().send().await;
         assert!(
             matches!(bucket_res, Ok(_)),
@@ -247,11 +410,12 @@ mod tests {
 
     /// Attempts to get and print all the objects inside a bucket
     /// This is only for robinbrick internal use. (This test is to be disabled, as it's only for demonstration purposes)
+    #[ignore = "requires live AWS credentials and the images-robinbrick bucket"]
     #[tokio::test]
     async fn get_all_objects_from_bucket() {
         let bucket_name = "images-robinbrick";
 
-        let client = start_s3_aws_connection().await;
+        let client = start_s3_aws_connection(&S3Config::default()).await.unwrap();
         let bucket_res = client.list_buckets().send().await;
         assert!(
             matches!(bucket_res, Ok(_)),
@@ -275,11 +439,12 @@ mod tests {
         println!("{:#?}", objects);
     }
 
+    #[ignore = "requires live AWS credentials and the images-robinbrick bucket"]
     #[tokio::test]
     async fn upload_png_to_bucket_and_get_back_url() {
         let bucket_name = "images-robinbrick";
         let file_name = "aaaa.png";
-        let client = start_s3_aws_connection().await;
+        let client = start_s3_aws_connection(&S3Config::default()).await.unwrap();
         let bucket_res = client.list_buckets().send().await;
         assert!(
             matches!(bucket_res, Ok(_)),
@@ -300,11 +465,11 @@ mod tests {
             .await;
         println!("{}{}", crate::BASE_BUCKET_URL, file_name);
     }
-    /// Tests the upload method that this whole library is about.
+    /// Tests the upload method that this whole library is about, against an in-memory backend
+    /// so it runs deterministically and offline.
     #[tokio::test]
     async fn test_upload_both() {
-        // Get a client first
-        let client = start_s3_aws_connection().await;
+        let storage = MockBackend::default();
         let image_in_base64 = include_str!("../testimage.txt");
         let image_with_bg = change_background(
             EncodedImage {
@@ -314,7 +479,7 @@ mod tests {
         )
         .unwrap();
         let result1 = upload_image_in_base64(
-            &client,
+            &storage,
             EncodedImage {
                 bytes: image_in_base64,
             },
@@ -322,40 +487,53 @@ mod tests {
         )
         .await;
         let result2 = upload_image_in_base64(
-            &client,
+            &storage,
             EncodedImage {
                 bytes: image_with_bg.as_str(),
             },
             None,
         )
         .await;
-        println!("{:#?}", result1);
-        println!("{:#?}", result2);
         assert!(matches!(result1, Ok(_)) && matches!(result2, Ok(_)));
-        assert!(
-            result1.unwrap().starts_with(BASE_BUCKET_URL)
-                && result2.unwrap().starts_with(BASE_BUCKET_URL)
+
+        let url1 = result1.unwrap();
+        let url2 = result2.unwrap();
+        assert!(url1.starts_with(storage.base_url()));
+        assert!(url2.starts_with(storage.base_url()));
+
+        let key1 = url1.strip_prefix(storage.base_url()).unwrap();
+        let key2 = url2.strip_prefix(storage.base_url()).unwrap();
+        assert_eq!(
+            storage.get(key1).await.unwrap(),
+            image_base64::from_base64(image_in_base64.to_string())
+        );
+        assert_eq!(
+            storage.get(key2).await.unwrap(),
+            image_base64::from_base64(image_with_bg)
         );
     }
-    /// Tests the upload method that this whole library is about. (Named)
+    /// Tests the upload method that this whole library is about. (Named), against an in-memory
+    /// backend so it runs deterministically and offline.
     #[tokio::test]
     async fn test_upload_named() {
-        // Get a client first
-        let client = start_s3_aws_connection().await;
+        let storage = MockBackend::default();
         let image_in_base64 = include_str!("../testimage.txt");
         let result = upload_image_in_base64(
-            &client,
+            &storage,
             EncodedImage {
                 bytes: image_in_base64,
             },
             Some("testimage12345687.png"),
         )
         .await;
-        println!("{:#?}", result);
         assert!(matches!(result, Ok(_)));
         assert_eq!(
             result.unwrap(),
-            format!("{BASE_BUCKET_URL}testimage12345687.png")
+            format!("{}testimage12345687.png", storage.base_url())
+        );
+        assert_eq!(
+            storage.get("testimage12345687.png").await.unwrap(),
+            image_base64::from_base64(image_in_base64.to_string())
         );
     }
 }
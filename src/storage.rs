@@ -0,0 +1,21 @@
+use async_trait::async_trait;
+
+/// Error returned by a `Storage` implementation.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StorageError {
+    #[default]
+    PutFailed,
+    GetFailed,
+    NotFound,
+}
+
+/// Abstracts over where image bytes actually live, so the image operations in this crate
+/// (`upload_image_in_base64`, the responsive-rendition pipeline, ...) don't need to know whether
+/// they're talking to S3, a local filesystem, or an in-memory store for tests.
+#[async_trait]
+pub trait Storage {
+    /// Stores `bytes` under `key` and returns the URL the stored object can be reached at.
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<String, StorageError>;
+    /// Retrieves the bytes stored under `key`.
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError>;
+}